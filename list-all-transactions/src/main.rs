@@ -1,23 +1,82 @@
+mod store;
+
 use chrono::NaiveDateTime;
 use clap::Parser;
-use serde::{Deserialize, Serialize};
-use std::collections::BTreeMap;
-use std::path::PathBuf;
+use rayon::prelude::*;
+use serde::{Deserialize, Deserializer as _, Serialize};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::path::{Path, PathBuf};
+use store::Store;
 use tabled::settings::object::{Columns, Object, Rows};
 use tabled::settings::{Alignment, Modify};
 use tabled::Tabled;
 
 #[derive(Debug, Parser)]
 struct Args {
-    /// Path to the transactions in JSON format.
-    transactions: PathBuf,
+    /// Path to the transactions in JSON format. Required unless
+    /// `--query-height` is used against an already-populated `--store`.
+    transactions: Option<PathBuf>,
 
     /// A file that contains records of address => alias.
     #[clap(long)]
     aliases: Option<PathBuf>,
 
+    /// Directory holding a persistent, on-disk store of parsed transactions
+    /// indexed by height. When given, every parsed record is appended here
+    /// so later runs can answer `--query-height` without re-parsing the JSON.
+    #[clap(long)]
+    store: Option<PathBuf>,
+
+    /// Look up a single height directly in `--store` and print it instead of
+    /// building the full report.
+    #[clap(long, requires = "store")]
+    query_height: Option<u64>,
+
+    /// The output format for the transaction dump.
+    #[clap(long, value_enum, default_value_t = OutputFormat::Csv)]
+    format: OutputFormat,
+
+    /// Addresses to highlight in the summary table, terminated by `;`.
+    #[clap(long, num_args = 1.., value_terminator = ";")]
+    highlight: Vec<String>,
+
+    /// Drop every address not in `--highlight` from the mint table, send
+    /// table, totals and CSV output.
+    #[clap(long, requires = "highlight")]
+    highlight_only: bool,
+
+    /// What to do when the same recipient address appears twice in a single
+    /// mint argument, or the same (height, method, hash) triple recurs.
+    #[clap(long, value_enum, default_value_t = OnDuplicateKey::Error)]
+    on_duplicate_key: OnDuplicateKey,
+
     /// The output file. Optional, by default will output to STDOUT.
     output: Option<PathBuf>,
+
+    /// Optional CSV file for the net settlement/clawback report (address,
+    /// alias, net residual balance from the duplication event).
+    #[clap(long)]
+    net_output: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OutputFormat {
+    /// The original flat CSV dump (height, address, alias, amount).
+    Csv,
+    /// Balanced double-entry postings, importable by beancount/hledger.
+    Beancount,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, clap::ValueEnum)]
+enum OnDuplicateKey {
+    /// Abort, reporting the offending address and height.
+    Error,
+    /// Sum the amounts of a recipient address that recurs in one mint
+    /// argument. A repeated (height, method, hash) record is kept once, as
+    /// with `first`.
+    Sum,
+    /// Keep the first occurrence and silently drop the rest.
+    First,
 }
 
 #[derive(Deserialize)]
@@ -33,7 +92,7 @@ struct RawDuplicatedTransaction {
     neighborhood: String,
 }
 
-#[derive(Debug)]
+#[derive(Debug, Serialize, Deserialize)]
 struct DuplicatedTransaction {
     orig_time: NaiveDateTime,
     max_time: NaiveDateTime,
@@ -44,6 +103,41 @@ struct DuplicatedTransaction {
     neighborhood: u64,
 }
 
+/// Drop or reject records whose `(height, method, hash)` triple recurs,
+/// per `on_duplicate_key`. Malformed or adversarial dumps can otherwise
+/// double-count a transaction that should only appear once.
+fn dedupe_top_level(
+    records: Vec<RawDuplicatedTransaction>,
+    on_duplicate_key: OnDuplicateKey,
+) -> Vec<RawDuplicatedTransaction> {
+    let mut seen: BTreeSet<(String, String, String)> = BTreeSet::new();
+    let mut out = Vec::with_capacity(records.len());
+    for record in records {
+        let key = (
+            record.height.clone(),
+            record.method.clone(),
+            record.hash.clone(),
+        );
+        if seen.contains(&key) {
+            match on_duplicate_key {
+                OnDuplicateKey::Error => panic!(
+                    "duplicate record (height={}, method={}, hash={}) in input",
+                    key.0, key.1, key.2
+                ),
+                // `record` is a repeat of the *same* (height, method, hash)
+                // transaction, not a distinct one whose amounts should add
+                // together - keep the first copy and drop the rest, same as
+                // `First`, or its amounts would be silently doubled.
+                OnDuplicateKey::Sum | OnDuplicateKey::First => {}
+            }
+        } else {
+            seen.insert(key);
+            out.push(record);
+        }
+    }
+    out
+}
+
 impl From<RawDuplicatedTransaction> for DuplicatedTransaction {
     fn from(value: RawDuplicatedTransaction) -> Self {
         Self {
@@ -66,25 +160,108 @@ impl From<RawDuplicatedTransaction> for DuplicatedTransaction {
 
 type AliasMap = std::collections::BTreeMap<String, String>;
 
+/// A mint `argument` object, deserialized strictly: every `address: amount`
+/// entry is kept, even if the same address recurs, so duplicates can be
+/// detected instead of silently overwritten the way a `BTreeMap` would.
+struct MintArgument(Vec<(String, String)>);
+
+impl<'de> Deserialize<'de> for MintArgument {
+    fn deserialize<D>(deserializer: D) -> Result<Self, D::Error>
+    where
+        D: serde::Deserializer<'de>,
+    {
+        struct MintArgumentVisitor;
+
+        impl<'de> serde::de::Visitor<'de> for MintArgumentVisitor {
+            type Value = MintArgument;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a JSON object mapping addresses to amount strings")
+            }
+
+            fn visit_map<A>(self, mut map: A) -> Result<Self::Value, A::Error>
+            where
+                A: serde::de::MapAccess<'de>,
+            {
+                let mut entries = Vec::with_capacity(map.size_hint().unwrap_or(0));
+                while let Some((address, amount)) = map.next_entry::<String, String>()? {
+                    entries.push((address, amount));
+                }
+                Ok(MintArgument(entries))
+            }
+        }
+
+        deserializer.deserialize_map(MintArgumentVisitor)
+    }
+}
+
+/// Collapse a mint argument's `(address, amount)` entries into per-address
+/// totals, applying `on_duplicate_key` whenever the same address recurs.
+fn dedupe_mint_argument(
+    entries: Vec<(String, String)>,
+    height: u64,
+    on_duplicate_key: OnDuplicateKey,
+) -> BTreeMap<String, u64> {
+    let mut out: BTreeMap<String, u64> = BTreeMap::new();
+    for (address, amount) in entries {
+        let amount: u64 = amount.parse().unwrap();
+        if let Some(existing) = out.get(&address).copied() {
+            match on_duplicate_key {
+                OnDuplicateKey::Error => panic!(
+                    "duplicate recipient address {address} in mint argument at height {height}"
+                ),
+                OnDuplicateKey::Sum => {
+                    out.insert(address, existing + amount);
+                }
+                OnDuplicateKey::First => {}
+            }
+        } else {
+            out.insert(address, amount);
+        }
+    }
+    out
+}
+
 #[derive(Default, Debug, Serialize)]
 struct TransposedMintTable(BTreeMap<u64, BTreeMap<String, u64>>);
 
 impl TransposedMintTable {
-    pub fn insert(&mut self, entry: DuplicatedTransaction) {
+    pub fn insert(&mut self, entry: DuplicatedTransaction, on_duplicate_key: OnDuplicateKey) {
         if entry.method != "tokens.mint" {
             return;
         }
 
         let argument = entry.argument.as_ref().unwrap();
-        let argument: BTreeMap<String, String> = serde_json::from_str(argument).unwrap();
+        let MintArgument(entries) = serde_json::from_str(argument).unwrap();
+        let height = entry.heights.first().copied().unwrap_or_default();
+        let argument = dedupe_mint_argument(entries, height, on_duplicate_key);
 
         // We ignore the first one as it is the only _valid_ transaction.
         for (address, amount) in &argument {
             for height in &entry.heights[1..] {
                 let inner = self.0.entry(*height).or_default();
-                *inner.entry(address.clone()).or_default() += amount.parse::<u64>().unwrap();
+                *inner.entry(address.clone()).or_default() += amount;
+            }
+        }
+    }
+
+    /// Merge another table's partial results into this one, summing overlapping entries.
+    pub fn merge(mut self, other: Self) -> Self {
+        for (height, entries) in other.0 {
+            let inner = self.0.entry(height).or_default();
+            for (address, amount) in entries {
+                *inner.entry(address).or_default() += amount;
             }
         }
+        self
+    }
+
+    /// Keep only entries whose address is in `addresses`, dropping now-empty heights.
+    pub fn retain_addresses(&mut self, addresses: &BTreeSet<String>) {
+        for entries in self.0.values_mut() {
+            entries.retain(|address, _| addresses.contains(address));
+        }
+        self.0.retain(|_, entries| !entries.is_empty());
     }
 }
 
@@ -109,6 +286,20 @@ impl TransposedSendTable {
         let LedgerSendArgument { from, to, amount } = serde_json::from_str(argument).unwrap();
         *self.0.entry((from, to)).or_default() += amount;
     }
+
+    /// Merge another table's partial results into this one, summing overlapping entries.
+    pub fn merge(mut self, other: Self) -> Self {
+        for (key, amount) in other.0 {
+            *self.0.entry(key).or_default() += amount;
+        }
+        self
+    }
+
+    /// Keep only entries where `from` or `to` is in `addresses`.
+    pub fn retain_addresses(&mut self, addresses: &BTreeSet<String>) {
+        self.0
+            .retain(|(from, to), _| addresses.contains(from) || addresses.contains(to));
+    }
 }
 
 #[derive(Tabled)]
@@ -116,21 +307,455 @@ struct SummaryRow {
     address: String,
     alias: String,
     total: u64,
+    highlight: String,
+}
+
+/// A single double-entry posting within a [`Transaction`].
+struct Posting {
+    account: String,
+    amount: i64,
+    commodity: String,
+}
+
+/// A balanced double-entry transaction, renderable as beancount/hledger text.
+struct Transaction {
+    date: NaiveDateTime,
+    narration: String,
+    postings: Vec<Posting>,
+}
+
+impl Transaction {
+    /// Panics (via `debug_assert`) if the postings don't sum to zero.
+    fn to_beancount(&self) -> String {
+        debug_assert_eq!(
+            self.postings.iter().map(|p| p.amount).sum::<i64>(),
+            0,
+            "unbalanced transaction: {}",
+            self.narration
+        );
+
+        let mut out = format!(
+            "{} * \"{}\"\n",
+            self.date.format("%Y-%m-%d"),
+            self.narration
+        );
+        for p in &self.postings {
+            out.push_str(&format!(
+                "  {:<48} {} {}\n",
+                p.account, p.amount, p.commodity
+            ));
+        }
+        out
+    }
+}
+
+/// Resolve an address to its alias, falling back to the address itself.
+fn resolve_account(aliases: &AliasMap, address: &str) -> String {
+    aliases
+        .get(address)
+        .cloned()
+        .unwrap_or_else(|| address.to_string())
+}
+
+/// Fallback timestamp for transactions whose originating date couldn't be recovered.
+fn epoch() -> NaiveDateTime {
+    chrono::NaiveDate::from_ymd_opt(1970, 1, 1)
+        .unwrap()
+        .and_hms_opt(0, 0, 0)
+        .unwrap()
+}
+
+/// Generate one balanced transaction per (height, address) duplicated mint.
+fn mint_transactions(
+    mint_table: &TransposedMintTable,
+    height_dates: &BTreeMap<u64, NaiveDateTime>,
+    aliases: &AliasMap,
+) -> Vec<Transaction> {
+    let mut out = Vec::new();
+    for (height, entry) in &mint_table.0 {
+        let date = height_dates.get(height).copied().unwrap_or_else(epoch);
+        for (address, amount) in entry {
+            let account = resolve_account(aliases, address);
+            let amount = *amount as i64;
+            out.push(Transaction {
+                date,
+                narration: format!("Duplicated mint at height {height}"),
+                postings: vec![
+                    Posting {
+                        account: format!("Liabilities:Duplicated:{account}"),
+                        amount,
+                        commodity: "TOK".to_string(),
+                    },
+                    Posting {
+                        account: "Income:Mint".to_string(),
+                        amount: -amount,
+                        commodity: "TOK".to_string(),
+                    },
+                ],
+            });
+        }
+    }
+    out
+}
+
+/// Generate one balanced transaction per `(from, to)` pair of duplicated
+/// `ledger.send`s, carrying the pair's summed amount.
+fn send_transactions(
+    send_table: &TransposedSendTable,
+    send_dates: &BTreeMap<(String, String), NaiveDateTime>,
+    aliases: &AliasMap,
+) -> Vec<Transaction> {
+    let mut out = Vec::new();
+    for ((from, to), amount) in &send_table.0 {
+        let date = send_dates
+            .get(&(from.clone(), to.clone()))
+            .copied()
+            .unwrap_or_else(epoch);
+        let from_account = resolve_account(aliases, from);
+        let to_account = resolve_account(aliases, to);
+        let amount = *amount as i64;
+        out.push(Transaction {
+            date,
+            narration: format!("Duplicated send {from} -> {to}"),
+            postings: vec![
+                Posting {
+                    account: format!("Assets:{from_account}"),
+                    amount: -amount,
+                    commodity: "TOK".to_string(),
+                },
+                Posting {
+                    account: format!("Assets:{to_account}"),
+                    amount,
+                    commodity: "TOK".to_string(),
+                },
+            ],
+        });
+    }
+    out
+}
+
+#[derive(Tabled)]
+struct NetRow {
+    address: String,
+    alias: String,
+    net: i64,
+}
+
+/// Reconcile the mint and send tables into a single per-address residual
+/// balance: every duplicated mint credits the recipient, and every
+/// duplicated send debits the sender and credits the receiver in turn, so a
+/// recipient who re-sends duplicated funds only has the portion it actually
+/// still holds reported. Acyclic edges are pushed exactly once each, in
+/// topological order (a node's balance is final by the time it's visited,
+/// since every edge feeding it has already been processed) for an O(V+E)
+/// pass. Edges touching a cycle reported by `find_send_cycles` are left
+/// alone entirely, since there's no well-defined drain order for a loop;
+/// those addresses keep their raw mint-credited balance and the cycle
+/// printout flags them for manual review instead.
+fn net_positions(
+    mint_table: &TransposedMintTable,
+    send_table: &TransposedSendTable,
+) -> BTreeMap<String, i64> {
+    let mut net: BTreeMap<String, i64> = BTreeMap::new();
+    for entries in mint_table.0.values() {
+        for (address, amount) in entries {
+            *net.entry(address.clone()).or_default() += *amount as i64;
+        }
+    }
+
+    let cyclic: BTreeSet<String> = find_send_cycles(send_table).into_iter().flatten().collect();
+
+    let mut out_edges: BTreeMap<&str, Vec<(&str, i64)>> = BTreeMap::new();
+    let mut in_degree: BTreeMap<&str, usize> = BTreeMap::new();
+    for ((from, to), amount) in &send_table.0 {
+        if cyclic.contains(from) || cyclic.contains(to) {
+            continue;
+        }
+        out_edges
+            .entry(from.as_str())
+            .or_default()
+            .push((to.as_str(), *amount as i64));
+        in_degree.entry(from.as_str()).or_insert(0);
+        *in_degree.entry(to.as_str()).or_insert(0) += 1;
+    }
+
+    let mut queue: VecDeque<&str> = in_degree
+        .iter()
+        .filter(|(_, &degree)| degree == 0)
+        .map(|(&node, _)| node)
+        .collect();
+
+    while let Some(from) = queue.pop_front() {
+        let Some(edges) = out_edges.get(from) else {
+            continue;
+        };
+        for &(to, capacity) in edges {
+            let available = net.get(from).copied().unwrap_or(0).max(0);
+            let moved = available.min(capacity);
+            if moved > 0 {
+                *net.entry(from.to_string()).or_default() -= moved;
+                *net.entry(to.to_string()).or_default() += moved;
+            }
+            let degree = in_degree.get_mut(to).expect("to was seeded above");
+            *degree -= 1;
+            if *degree == 0 {
+                queue.push_back(to);
+            }
+        }
+    }
+
+    net
+}
+
+/// Detect directed cycles in the `ledger.send` graph (a re-send chain that
+/// loops back on an earlier address), so operators can flag them for manual
+/// review instead of trusting the net position blindly.
+fn find_send_cycles(send_table: &TransposedSendTable) -> Vec<Vec<String>> {
+    let mut edges: BTreeMap<&str, Vec<&str>> = BTreeMap::new();
+    for (from, to) in send_table.0.keys() {
+        edges.entry(from.as_str()).or_default().push(to.as_str());
+    }
+
+    #[derive(Clone, Copy, PartialEq)]
+    enum State {
+        InProgress,
+        Done,
+    }
+
+    fn visit<'a>(
+        node: &'a str,
+        edges: &BTreeMap<&'a str, Vec<&'a str>>,
+        state: &mut BTreeMap<&'a str, State>,
+        stack: &mut Vec<&'a str>,
+        cycles: &mut Vec<Vec<String>>,
+    ) {
+        state.insert(node, State::InProgress);
+        stack.push(node);
+        for &next in edges.get(node).into_iter().flatten() {
+            match state.get(next) {
+                None => visit(next, edges, state, stack, cycles),
+                Some(State::InProgress) => {
+                    let start = stack.iter().position(|&n| n == next).unwrap();
+                    let mut cycle: Vec<String> =
+                        stack[start..].iter().map(|s| s.to_string()).collect();
+                    cycle.push(next.to_string());
+                    cycles.push(cycle);
+                }
+                Some(State::Done) => {}
+            }
+        }
+        stack.pop();
+        state.insert(node, State::Done);
+    }
+
+    let mut state: BTreeMap<&str, State> = BTreeMap::new();
+    let mut cycles = Vec::new();
+    for &node in edges.keys() {
+        if !state.contains_key(node) {
+            let mut stack = Vec::new();
+            visit(node, &edges, &mut state, &mut stack, &mut cycles);
+        }
+    }
+    cycles
+}
+
+/// Combine the date maps built by each rayon worker, keeping `a`'s date on
+/// conflict: rayon reduces partials in input order, so the date of the record
+/// that comes first in the input wins, as it does within one worker's fold.
+fn merge_dates<K: Ord>(
+    mut a: BTreeMap<K, NaiveDateTime>,
+    b: BTreeMap<K, NaiveDateTime>,
+) -> BTreeMap<K, NaiveDateTime> {
+    for (k, v) in b {
+        a.entry(k).or_insert(v);
+    }
+    a
+}
+
+type HeightDates = BTreeMap<u64, NaiveDateTime>;
+type SendDates = BTreeMap<(String, String), NaiveDateTime>;
+type PartialAccum = (
+    TransposedMintTable,
+    TransposedSendTable,
+    HeightDates,
+    SendDates,
+    // Amounts that came specifically from a genuinely duplicated send
+    // (`heights.len() > 1`), kept separate from `TransposedSendTable` (index
+    // 1) which aggregates every send regardless of duplication status. An
+    // edge with both an ordinary and a duplicated send between the same two
+    // addresses must only have the duplicated portion reconciled, not the
+    // combined total.
+    TransposedSendTable,
+);
+
+/// Fold a single transaction into the mint/send tables (and their side date
+/// maps), shared by both the parallel, fully in-memory path and the
+/// sequential `--store` streaming path so the two can't drift apart.
+fn fold_transaction(
+    acc: &mut PartialAccum,
+    t: DuplicatedTransaction,
+    on_duplicate_key: OnDuplicateKey,
+) {
+    if t.method == "tokens.mint" {
+        for height in &t.heights[1..] {
+            acc.2.entry(*height).or_insert(t.orig_time);
+        }
+        acc.0.insert(t, on_duplicate_key);
+    } else if t.method == "ledger.send" {
+        if let Some(arg) = &t.argument {
+            if let Ok(LedgerSendArgument { from, to, amount }) = serde_json::from_str(arg) {
+                acc.3
+                    .entry((from.clone(), to.clone()))
+                    .or_insert(t.orig_time);
+                if t.heights.len() > 1 {
+                    *acc.4 .0.entry((from, to)).or_default() += amount;
+                }
+            }
+        }
+        acc.1.insert(t);
+    }
+}
+
+/// Stream `path`'s top-level JSON array one element at a time via
+/// `on_record`, without ever materializing the whole array in memory - only
+/// the raw `serde_json` read buffer and whatever bookkeeping `on_record`
+/// itself keeps. Used by `--store` mode so a dump far larger than RAM can
+/// still be processed.
+fn stream_raw_transactions(path: &Path, on_record: impl FnMut(RawDuplicatedTransaction)) {
+    struct SeqVisitor<F>(F);
+
+    impl<'de, F: FnMut(RawDuplicatedTransaction)> serde::de::Visitor<'de> for SeqVisitor<F> {
+        type Value = ();
+
+        fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+            f.write_str("a JSON array of duplicated-transaction records")
+        }
+
+        fn visit_seq<A>(mut self, mut seq: A) -> Result<Self::Value, A::Error>
+        where
+            A: serde::de::SeqAccess<'de>,
+        {
+            while let Some(record) = seq.next_element::<RawDuplicatedTransaction>()? {
+                (self.0)(record);
+            }
+            Ok(())
+        }
+    }
+
+    let file = std::fs::File::open(path).unwrap();
+    let reader = std::io::BufReader::new(file);
+    serde_json::Deserializer::from_reader(reader)
+        .deserialize_seq(SeqVisitor(on_record))
+        .unwrap();
 }
 
 fn main() {
     let Args {
         transactions,
         aliases,
+        store,
+        query_height,
+        format,
+        highlight,
+        highlight_only,
+        on_duplicate_key,
         output,
+        net_output,
     } = Args::parse();
     eprintln!("args: {:?}", Args::parse());
 
-    // Load transactions.
-    let transactions = std::fs::read_to_string(transactions).unwrap();
-    let transactions: Vec<RawDuplicatedTransaction> = serde_json::from_str(&transactions).unwrap();
-    let transactions: Vec<DuplicatedTransaction> =
-        transactions.into_iter().map(|x| x.into()).collect();
+    if let Some(height) = query_height {
+        let mut store = Store::open(store.as_ref().unwrap()).unwrap();
+        let entries = store.get(height).unwrap();
+        if entries.is_empty() {
+            eprintln!("no record found at height {height}");
+        } else {
+            for entry in &entries {
+                println!("{entry:#?}");
+            }
+        }
+        return;
+    }
+
+    let highlight: BTreeSet<String> = highlight.into_iter().collect();
+
+    let new_accum = || -> PartialAccum {
+        (
+            TransposedMintTable::default(),
+            TransposedSendTable::default(),
+            BTreeMap::new(),
+            BTreeMap::new(),
+            TransposedSendTable::default(),
+        )
+    };
+
+    let (mut mint_table, mut send_table, height_dates, send_dates, mut duplicated_send_table) =
+        if let Some(dir) = &store {
+            // A dump large enough to need `--store` is a dump large enough
+            // not to fit in RAM as a `Vec`, so this path never holds more
+            // than one record at a time: stream the JSON array element by
+            // element, append each parsed record to the store immediately,
+            // and fold it into the mint/send tables in the same pass.
+            let path =
+                transactions.expect("TRANSACTIONS is required unless --query-height is used");
+            let mut store = Store::open(dir).unwrap();
+            let mut seen: BTreeSet<(String, String, String)> = BTreeSet::new();
+            let mut acc = new_accum();
+            stream_raw_transactions(&path, |record| {
+                let key = (
+                    record.height.clone(),
+                    record.method.clone(),
+                    record.hash.clone(),
+                );
+                if seen.contains(&key) {
+                    match on_duplicate_key {
+                        OnDuplicateKey::Error => panic!(
+                            "duplicate record (height={}, method={}, hash={}) in input",
+                            key.0, key.1, key.2
+                        ),
+                        OnDuplicateKey::Sum | OnDuplicateKey::First => return,
+                    }
+                }
+                seen.insert(key);
+
+                let t = DuplicatedTransaction::from(record);
+                store.append(&t).unwrap();
+                fold_transaction(&mut acc, t, on_duplicate_key);
+            });
+            acc
+        } else {
+            // Load transactions.
+            let transactions = std::fs::read_to_string(
+                transactions.expect("TRANSACTIONS is required unless --query-height is used"),
+            )
+            .unwrap();
+            let transactions: Vec<RawDuplicatedTransaction> =
+                serde_json::from_str(&transactions).unwrap();
+            let transactions = dedupe_top_level(transactions, on_duplicate_key);
+            let transactions: Vec<DuplicatedTransaction> = transactions
+                .into_par_iter()
+                .map(DuplicatedTransaction::from)
+                .collect();
+
+            // Parse and fold every record into the mint/send tables in
+            // parallel: each worker accumulates its own partials, which are
+            // then merged pairwise.
+            transactions
+                .into_par_iter()
+                .fold(new_accum, |mut acc, t| {
+                    fold_transaction(&mut acc, t, on_duplicate_key);
+                    acc
+                })
+                .reduce(new_accum, |a, b| {
+                    (
+                        a.0.merge(b.0),
+                        a.1.merge(b.1),
+                        merge_dates(a.2, b.2),
+                        merge_dates(a.3, b.3),
+                        a.4.merge(b.4),
+                    )
+                })
+        };
 
     // Load aliases.
     let aliases = aliases.map(|x| std::fs::read_to_string(x).unwrap());
@@ -138,33 +763,57 @@ fn main() {
         .map(|x| serde_json::from_str(&x).unwrap())
         .unwrap_or_default();
 
-    // Filter transactions we're not interested in.
-    let transactions: Vec<DuplicatedTransaction> = transactions.into_iter().collect();
-
-    let mut mint_table = TransposedMintTable::default();
-    let mut send_table = TransposedSendTable::default();
-
-    for t in transactions {
-        if t.method == "tokens.mint" {
-            mint_table.insert(t);
-        } else if t.method == "ledger.send" {
-            eprintln!("...");
-            send_table.insert(t);
-        }
+    if highlight_only && !highlight.is_empty() {
+        mint_table.retain_addresses(&highlight);
+        send_table.retain_addresses(&highlight);
+        duplicated_send_table.retain_addresses(&highlight);
     }
 
     let mut output_csv = csv::Writer::from_writer(vec![]);
     let mut totals: BTreeMap<String, u64> = BTreeMap::new();
-    for (height, entry) in mint_table.0 {
+    for (height, entry) in &mint_table.0 {
         for (address, amount) in entry {
-            let alias = aliases.get(&address).cloned().unwrap_or_default();
+            let alias = aliases.get(address).cloned().unwrap_or_default();
             *totals.entry(address.clone()).or_default() += amount;
             output_csv
-                .write_record(&[height.to_string(), address, alias, amount.to_string()])
+                .write_record(&[
+                    height.to_string(),
+                    address.clone(),
+                    alias,
+                    amount.to_string(),
+                ])
                 .unwrap();
         }
     }
-    let data = String::from_utf8(output_csv.into_inner().unwrap()).unwrap();
+
+    // `totals` (and thus the summary table below) is otherwise built purely
+    // from mint recipients, so an address that only shows up as a send
+    // `from`/`to` would never get marked by `--highlight`. Seed it in at 0 so
+    // send-only matches still appear (and get starred) in the summary.
+    for (from, to) in send_table.0.keys() {
+        if highlight.contains(from) {
+            totals.entry(from.clone()).or_insert(0);
+        }
+        if highlight.contains(to) {
+            totals.entry(to.clone()).or_insert(0);
+        }
+    }
+
+    let data = match format {
+        OutputFormat::Csv => String::from_utf8(output_csv.into_inner().unwrap()).unwrap(),
+        OutputFormat::Beancount => {
+            let mut txs = mint_transactions(&mint_table, &height_dates, &aliases);
+            txs.extend(send_transactions(
+                &duplicated_send_table,
+                &send_dates,
+                &aliases,
+            ));
+            txs.iter()
+                .map(Transaction::to_beancount)
+                .collect::<Vec<_>>()
+                .join("\n")
+        }
+    };
 
     let summary = totals
         .iter()
@@ -172,6 +821,11 @@ fn main() {
             address: address.clone(),
             alias: aliases.get(address).cloned().unwrap_or_default(),
             total: *total,
+            highlight: if highlight.contains(address) {
+                "*".to_string()
+            } else {
+                String::new()
+            },
         })
         .collect::<Vec<_>>();
 
@@ -208,4 +862,234 @@ fn main() {
             amount
         );
     }
+
+    let cycles = find_send_cycles(&duplicated_send_table);
+    if !cycles.is_empty() {
+        eprintln!(
+            "\n! Cycles detected in the send graph (net positions below may need manual review):"
+        );
+        for cycle in &cycles {
+            eprintln!("  {}", cycle.join(" -> "));
+        }
+    }
+
+    let net = net_positions(&mint_table, &duplicated_send_table);
+    let net_summary = net
+        .iter()
+        .map(|(address, net)| NetRow {
+            address: address.clone(),
+            alias: aliases.get(address).cloned().unwrap_or_default(),
+            net: *net,
+        })
+        .collect::<Vec<_>>();
+
+    if let Some(path) = net_output {
+        let mut net_csv = csv::Writer::from_writer(vec![]);
+        for row in &net_summary {
+            net_csv
+                .write_record(&[row.address.clone(), row.alias.clone(), row.net.to_string()])
+                .unwrap();
+        }
+        std::fs::write(path, net_csv.into_inner().unwrap()).unwrap();
+    }
+
+    eprintln!("\n# Net settlement / clawback plan");
+    eprintln!(
+        "{}",
+        tabled::Table::new(net_summary)
+            .with(tabled::settings::Style::markdown())
+            .with(Modify::new(Rows::new(1..).and(Columns::last())).with(Alignment::right()))
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn entries(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs
+            .iter()
+            .map(|(a, b)| (a.to_string(), b.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn dedupe_mint_argument_sum_adds_duplicate_addresses() {
+        let out = dedupe_mint_argument(
+            entries(&[("addr1", "3"), ("addr1", "2"), ("addr2", "7")]),
+            100,
+            OnDuplicateKey::Sum,
+        );
+        assert_eq!(out.get("addr1"), Some(&5));
+        assert_eq!(out.get("addr2"), Some(&7));
+    }
+
+    #[test]
+    fn dedupe_mint_argument_first_keeps_first_occurrence() {
+        let out = dedupe_mint_argument(
+            entries(&[("addr1", "3"), ("addr1", "2")]),
+            100,
+            OnDuplicateKey::First,
+        );
+        assert_eq!(out.get("addr1"), Some(&3));
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate recipient address")]
+    fn dedupe_mint_argument_error_panics_on_duplicate() {
+        dedupe_mint_argument(
+            entries(&[("addr1", "3"), ("addr1", "2")]),
+            100,
+            OnDuplicateKey::Error,
+        );
+    }
+
+    fn send_table(edges: &[(&str, &str, u64)]) -> TransposedSendTable {
+        TransposedSendTable(
+            edges
+                .iter()
+                .map(|(from, to, amount)| ((from.to_string(), to.to_string()), *amount))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn net_positions_pushes_balances_along_an_acyclic_chain() {
+        let mut mint_table = TransposedMintTable::default();
+        mint_table
+            .0
+            .entry(1)
+            .or_default()
+            .insert("a".to_string(), 10);
+        let send_table = send_table(&[("a", "b", 10), ("b", "c", 10)]);
+
+        let net = net_positions(&mint_table, &send_table);
+        assert_eq!(net.get("a").copied(), Some(0));
+        assert_eq!(net.get("b").copied(), Some(0));
+        assert_eq!(net.get("c").copied(), Some(10));
+    }
+
+    #[test]
+    fn net_positions_leaves_cyclic_edges_untouched_and_finishes_quickly() {
+        let mut mint_table = TransposedMintTable::default();
+        mint_table
+            .0
+            .entry(1)
+            .or_default()
+            .insert("a".to_string(), 1);
+        let send_table =
+            send_table(&[("a", "b", 5_000_000_000_000), ("b", "a", 5_000_000_000_000)]);
+
+        // Regression test: this used to hang (the same small balance was
+        // relaxed around the cycle one slice at a time) instead of finishing
+        // immediately.
+        let net = net_positions(&mint_table, &send_table);
+        assert_eq!(net.get("a").copied(), Some(1));
+    }
+
+    #[test]
+    fn find_send_cycles_detects_a_simple_cycle() {
+        let send_table = send_table(&[("a", "b", 1), ("b", "a", 1)]);
+        let cycles = find_send_cycles(&send_table);
+        assert_eq!(cycles.len(), 1);
+    }
+
+    #[test]
+    fn find_send_cycles_reports_nothing_for_an_acyclic_graph() {
+        let send_table = send_table(&[("a", "b", 1), ("b", "c", 1)]);
+        assert!(find_send_cycles(&send_table).is_empty());
+    }
+
+    fn raw_transaction(height: &str, method: &str, hash: &str) -> RawDuplicatedTransaction {
+        RawDuplicatedTransaction {
+            orig_time: "2024-01-01T00:00:00".to_string(),
+            max_time: "2024-01-01T00:00:00".to_string(),
+            method: method.to_string(),
+            height: height.to_string(),
+            hash: hash.to_string(),
+            argument: None,
+            neighborhood: "0".to_string(),
+        }
+    }
+
+    #[test]
+    fn dedupe_top_level_sum_drops_repeats_of_the_same_key_without_doubling() {
+        let records = vec![
+            raw_transaction("100", "tokens.mint", "h1"),
+            raw_transaction("100", "tokens.mint", "h1"),
+            raw_transaction("100", "tokens.mint", "h2"),
+        ];
+        let out = dedupe_top_level(records, OnDuplicateKey::Sum);
+        assert_eq!(out.len(), 2);
+    }
+
+    #[test]
+    fn dedupe_top_level_first_keeps_only_the_first_occurrence() {
+        let records = vec![
+            raw_transaction("100", "tokens.mint", "h1"),
+            raw_transaction("100", "tokens.mint", "h1"),
+        ];
+        let out = dedupe_top_level(records, OnDuplicateKey::First);
+        assert_eq!(out.len(), 1);
+    }
+
+    #[test]
+    #[should_panic(expected = "duplicate record")]
+    fn dedupe_top_level_error_panics_on_a_duplicate_key() {
+        let records = vec![
+            raw_transaction("100", "tokens.mint", "h1"),
+            raw_transaction("100", "tokens.mint", "h1"),
+        ];
+        dedupe_top_level(records, OnDuplicateKey::Error);
+    }
+
+    fn send_transaction(
+        heights: Vec<u64>,
+        from: &str,
+        to: &str,
+        amount: u64,
+    ) -> DuplicatedTransaction {
+        let time =
+            chrono::NaiveDateTime::parse_from_str("2024-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap();
+        DuplicatedTransaction {
+            orig_time: time,
+            max_time: time,
+            method: "ledger.send".to_string(),
+            heights,
+            hash: bytes::Bytes::new(),
+            argument: Some(format!(
+                r#"{{"from":"{from}","to":"{to}","amount":{amount}}}"#
+            )),
+            neighborhood: 0,
+        }
+    }
+
+    #[test]
+    fn fold_transaction_only_counts_the_duplicated_portion_of_a_send_as_duplicated() {
+        let mut acc = (
+            TransposedMintTable::default(),
+            TransposedSendTable::default(),
+            HeightDates::new(),
+            SendDates::new(),
+            TransposedSendTable::default(),
+        );
+
+        // An ordinary, non-duplicated send...
+        fold_transaction(
+            &mut acc,
+            send_transaction(vec![200], "a", "b", 1000),
+            OnDuplicateKey::Sum,
+        );
+        // ...and a genuinely duplicated send between the same two addresses.
+        fold_transaction(
+            &mut acc,
+            send_transaction(vec![200, 201], "a", "b", 30),
+            OnDuplicateKey::Sum,
+        );
+
+        let key = ("a".to_string(), "b".to_string());
+        assert_eq!(acc.1 .0.get(&key).copied(), Some(1030));
+        assert_eq!(acc.4 .0.get(&key).copied(), Some(30));
+    }
 }
@@ -0,0 +1,215 @@
+//! A persistent, on-disk store for [`DuplicatedTransaction`] records, indexed
+//! by block height. Mirrors a windowed-ledger layout: a fixed-stride index
+//! file maps `height -> head offset` by direct addressing (the index slot
+//! for `height` lives at byte `height * INDEX_RECORD_LEN`), and a side-by-side
+//! data file holds two kinds of frames. The bincode-serialized payload for an
+//! entry is written exactly once, as a `[len][bytes]` frame. Each of the
+//! entry's heights then gets its own small chain-link frame, `[prev][payload
+//! offset]`, pointing at that one shared payload instead of carrying another
+//! copy of it. Two different duplicated transactions can land at the same
+//! height (e.g. a duplicated mint and a duplicated send in the same block),
+//! so `get` walks the whole chain instead of returning a single record and
+//! silently losing the rest. This lets a caller write a dump once and answer
+//! later `get(height)` queries with a handful of seeks into each file,
+//! instead of re-parsing the whole JSON dump.
+
+use crate::DuplicatedTransaction;
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::Path;
+
+const INDEX_RECORD_LEN: u64 = 8; // head offset: u64, little-endian.
+const NONE: u64 = u64::MAX; // sentinel: no record / no earlier record in the chain.
+
+pub struct Store {
+    index: File,
+    data: File,
+}
+
+impl Store {
+    /// Open the store rooted at `dir`, creating the index and data files if needed.
+    pub fn open(dir: impl AsRef<Path>) -> io::Result<Self> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir)?;
+        let index = OpenOptions::new()
+            .create(true)
+            .truncate(false)
+            .read(true)
+            .write(true)
+            .open(dir.join("index.bin"))?;
+        let data = OpenOptions::new()
+            .create(true)
+            .read(true)
+            .append(true)
+            .open(dir.join("data.bin"))?;
+        Ok(Self { index, data })
+    }
+
+    fn head(&mut self, height: u64) -> io::Result<u64> {
+        let slot = height * INDEX_RECORD_LEN;
+        if self.index.metadata()?.len() < slot + INDEX_RECORD_LEN {
+            return Ok(NONE);
+        }
+        self.index.seek(SeekFrom::Start(slot))?;
+        let mut buf = [0u8; INDEX_RECORD_LEN as usize];
+        self.index.read_exact(&mut buf)?;
+        // Slots skipped over when a higher height was written are zero-filled.
+        // Offset 0 always holds the first payload frame, never a link frame,
+        // so a zero slot means no record at this height.
+        match u64::from_le_bytes(buf) {
+            0 => Ok(NONE),
+            offset => Ok(offset),
+        }
+    }
+
+    /// Append `entry` to the data file, chaining it onto any record already
+    /// stored at each of its heights instead of overwriting it. The payload
+    /// itself is written once regardless of how many heights `entry` spans;
+    /// each height's chain-link frame only stores a pointer to it, so disk
+    /// usage scales with distinct records, not with duplication count.
+    pub fn append(&mut self, entry: &DuplicatedTransaction) -> io::Result<()> {
+        let bytes = bincode::serialize(entry).expect("DuplicatedTransaction is serializable");
+        let len = bytes.len() as u64;
+
+        let payload_offset = self.data.seek(SeekFrom::End(0))?;
+        self.data.write_all(&len.to_le_bytes())?;
+        self.data.write_all(&bytes)?;
+
+        for height in &entry.heights {
+            let prev = self.head(*height)?;
+
+            let frame_offset = self.data.seek(SeekFrom::End(0))?;
+            self.data.write_all(&prev.to_le_bytes())?;
+            self.data.write_all(&payload_offset.to_le_bytes())?;
+
+            self.index
+                .seek(SeekFrom::Start(*height * INDEX_RECORD_LEN))?;
+            self.index.write_all(&frame_offset.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    /// Fetch every record stored at `height`, most-recently-appended first,
+    /// by walking the chain of link frames rooted at the index's head
+    /// pointer and dereferencing each one's payload offset.
+    pub fn get(&mut self, height: u64) -> io::Result<Vec<DuplicatedTransaction>> {
+        let mut out = Vec::new();
+        let mut offset = self.head(height)?;
+        while offset != NONE {
+            self.data.seek(SeekFrom::Start(offset))?;
+            let mut link = [0u8; 16];
+            self.data.read_exact(&mut link)?;
+            let prev = u64::from_le_bytes(link[0..8].try_into().unwrap());
+            let payload_offset = u64::from_le_bytes(link[8..16].try_into().unwrap());
+
+            self.data.seek(SeekFrom::Start(payload_offset))?;
+            let mut len_buf = [0u8; 8];
+            self.data.read_exact(&mut len_buf)?;
+            let len = u64::from_le_bytes(len_buf);
+
+            let mut buf = vec![0u8; len as usize];
+            self.data.read_exact(&mut buf)?;
+            out.push(bincode::deserialize(&buf).expect("corrupt store record"));
+
+            offset = prev;
+        }
+        Ok(out)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeSet;
+
+    fn sample(heights: Vec<u64>, method: &str) -> DuplicatedTransaction {
+        let time =
+            chrono::NaiveDateTime::parse_from_str("2024-01-01 00:00:00", "%Y-%m-%d %H:%M:%S")
+                .unwrap();
+        DuplicatedTransaction {
+            orig_time: time,
+            max_time: time,
+            method: method.to_string(),
+            heights,
+            hash: bytes::Bytes::new(),
+            argument: None,
+            neighborhood: 0,
+        }
+    }
+
+    fn temp_dir(name: &str) -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "list-all-transactions-store-test-{name}-{}",
+            std::process::id()
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn round_trips_a_single_record() {
+        let dir = temp_dir("round-trip");
+        let mut store = Store::open(&dir).unwrap();
+        store.append(&sample(vec![100], "tokens.mint")).unwrap();
+
+        let found = store.get(100).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].method, "tokens.mint");
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn chains_records_that_share_a_height_instead_of_overwriting() {
+        let dir = temp_dir("chain");
+        let mut store = Store::open(&dir).unwrap();
+        store
+            .append(&sample(vec![100, 200], "tokens.mint"))
+            .unwrap();
+        store
+            .append(&sample(vec![150, 200], "ledger.send"))
+            .unwrap();
+
+        let found = store.get(200).unwrap();
+        let methods: BTreeSet<&str> = found.iter().map(|t| t.method.as_str()).collect();
+        assert_eq!(methods, BTreeSet::from(["tokens.mint", "ledger.send"]));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn a_record_spanning_several_heights_writes_its_payload_only_once() {
+        let dir = temp_dir("shared-payload");
+        let mut store = Store::open(&dir).unwrap();
+        let before = std::fs::metadata(dir.join("data.bin"))
+            .map(|m| m.len())
+            .unwrap_or(0);
+        store
+            .append(&sample(vec![100, 200, 300], "tokens.mint"))
+            .unwrap();
+        let after = std::fs::metadata(dir.join("data.bin")).unwrap().len();
+
+        // One payload frame (`[len][bytes]`) plus three 16-byte chain-link
+        // frames, not three full copies of the payload.
+        let payload_len = bincode::serialize(&sample(vec![100, 200, 300], "tokens.mint"))
+            .unwrap()
+            .len() as u64;
+        assert_eq!(after - before, 8 + payload_len + 3 * 16);
+
+        for height in [100, 200, 300] {
+            let found = store.get(height).unwrap();
+            assert_eq!(found.len(), 1);
+            assert_eq!(found[0].method, "tokens.mint");
+        }
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn missing_height_returns_empty() {
+        let dir = temp_dir("missing");
+        let mut store = Store::open(&dir).unwrap();
+        assert!(store.get(999).unwrap().is_empty());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}